@@ -1,11 +1,40 @@
 use std::env;
 use std::fs;
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, Seek, SeekFrom};
 use std::io::prelude::*;
+use std::os::unix::fs::MetadataExt;
 use std::time;
 use std::process;
 use std::path::Path;
 
+/*
+ * The progress record for a records file lives alongside it as a sidecar
+ * `.progress` file. It holds the line number of the last successfully
+ * processed instruction along with the running byte totals as of that
+ * line, so a crash partway through a large instruction file can resume
+ * from the exact point it left off instead of re-deleting (and re-logging)
+ * objects that are already gone.
+ */
+fn progress_file_path(records_file_path: &str) -> String {
+    format!("{}.progress", records_file_path)
+}
+
+fn read_progress(progress_file_path: &str) -> Option<(usize, u64, u64)> {
+    let contents = fs::read_to_string(progress_file_path).ok()?;
+    let mut fields = contents.trim().split('\t');
+    let line_no = fields.next()?.parse().ok()?;
+    let logical_bytes = fields.next()?.parse().ok()?;
+    let physical_bytes = fields.next()?.parse().ok()?;
+    Some((line_no, logical_bytes, physical_bytes))
+}
+
+fn write_progress(progress_file: &mut fs::File, line_no: usize, logical_bytes: u64, physical_bytes: u64) -> io::Result<()> {
+    progress_file.seek(SeekFrom::Start(0))?;
+    progress_file.set_len(0)?;
+    writeln!(progress_file, "{}\t{}\t{}", line_no, logical_bytes, physical_bytes)?;
+    progress_file.sync_all()
+}
+
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
 
@@ -27,6 +56,25 @@ fn main() -> io::Result<()> {
 
     let storage_id = &args[2].clone();
     let mut total_bytes_processed = args[3].parse::<u64>().unwrap();
+    // The starting physical total is a newer, optional argument so that gc
+    // keeps working against a wrapper script that hasn't been updated yet to
+    // pass it.
+    let mut total_physical_bytes_processed = args.get(4).and_then(|a| a.parse::<u64>().ok()).unwrap_or(0);
+
+    let progress_path = progress_file_path(records_file_path);
+    let mut resume_from_line = 0;
+    if let Some((line_no, logical_bytes, physical_bytes)) = read_progress(&progress_path) {
+        println!("Resuming {} from line {}", records_file_path, line_no);
+        resume_from_line = line_no;
+        total_bytes_processed = logical_bytes;
+        total_physical_bytes_processed = physical_bytes;
+    }
+
+    let mut progress_file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&progress_path)?;
 
     /*
      * If we encounter an invalid instruction we set this to true so that when we're done processing the
@@ -35,7 +83,12 @@ fn main() -> io::Result<()> {
      */
     let mut invalid_instruction_seen = false;
 
-    for line in records.lines() {
+    for (line_no, line) in records.lines().enumerate() {
+        let line_no = line_no + 1;
+        if line_no <= resume_from_line {
+            continue;
+        }
+
         let line_val = line.unwrap();
         println!("Processing {}", line_val);
 
@@ -64,12 +117,21 @@ fn main() -> io::Result<()> {
         let object = format!("/manta/{}/{}", line_cols[2], line_cols[3]);
         if !Path::new(&object).exists() {
             println!("Object: {} did not exist", object);
-            continue; 
+            continue;
         }
 
         let mut object_bytes = 0;
-        if let Ok(md) = fs::metadata(&object) { object_bytes += md.len() }
+        let mut object_physical_bytes = 0;
+        if let Ok(md) = fs::metadata(&object) {
+            object_bytes += md.len();
+            // st_blocks is always reported in 512-byte units regardless of the
+            // underlying filesystem's block size, so this reflects the actual
+            // space reclaimed on compressed/ZFS-backed storage rather than the
+            // object's logical length.
+            object_physical_bytes += md.blocks() * 512;
+        }
         total_bytes_processed += object_bytes;
+        total_physical_bytes_processed += object_physical_bytes;
 
         let sys_time = time::SystemTime::now().duration_since(time::SystemTime::UNIX_EPOCH).unwrap();
         let pid = process::id();
@@ -77,8 +139,8 @@ fn main() -> io::Result<()> {
         let sys_time_secs = sys_time.as_secs();
         let cur_logical_bytes = format!("{}: mako_gc.sh ({}) current logical bytes processed: {}\n", sys_time_secs, pid, object_bytes);
         let total_logical_bytes = format!("{}: mako_gc.sh ({}) total logical bytes deleted: {}\n", sys_time_secs, pid, total_bytes_processed);
-        let cur_physical_bytes = format!("{}: mako_gc.sh ({}) current physical bytes processed: 0\n", sys_time_secs, pid);
-        let total_physical_bytes = format!("{}: mako_gc.sh ({}) total physical bytes deleted: 0\n", sys_time_secs, pid);
+        let cur_physical_bytes = format!("{}: mako_gc.sh ({}) current physical bytes processed: {}\n", sys_time_secs, pid, object_physical_bytes);
+        let total_physical_bytes = format!("{}: mako_gc.sh ({}) total physical bytes deleted: {}\n", sys_time_secs, pid, total_physical_bytes_processed);
 
         bytes_processed_file.write_all(cur_logical_bytes.as_bytes())?;
         bytes_processed_file.write_all(total_logical_bytes.as_bytes())?;
@@ -86,9 +148,24 @@ fn main() -> io::Result<()> {
         bytes_processed_file.write_all(total_physical_bytes.as_bytes())?;
 
         fs::remove_file(object)?;
+
+        write_progress(&mut progress_file, line_no, total_bytes_processed, total_physical_bytes_processed)?;
     }
 
-    if invalid_instruction_seen { process::exit(1); }
+    if invalid_instruction_seen {
+        /*
+         * The instruction file is being preserved for postmortem analysis, so
+         * keep the checkpoint too -- otherwise the next run against this same
+         * file starts back at line 1 and re-processes everything we already
+         * got through.
+         */
+        process::exit(1);
+    }
+
+    /*
+     * We made it through the whole records file, so there's nothing left to resume from.
+     */
+    let _ = fs::remove_file(&progress_path);
 
     Ok(())
 }
\ No newline at end of file
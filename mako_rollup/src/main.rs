@@ -9,94 +9,669 @@
  * Copyright 2023 MNX Cloud, Inc.
  */
 use std::collections::HashMap;
-use std::path::Component;
-use std::time::{Instant, SystemTime};
-use walkdir::WalkDir;
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use rayon::prelude::*;
 
 struct Account {
     bytes: u64,
     objects: u64,
 }
 
-fn main() {
-    let start = Instant::now();
-    let mut accounts: HashMap<String, Account> = HashMap::new();
-
-    // Traverse the manta directory to build up our HashMap of object and byte counts
-    for entry in WalkDir::new("/manta") {
-        let entry = entry.unwrap();
-        let metadata = entry.path().metadata().expect("metadata call failed");
-
-        // We only care about files, so we intentionally do nothing with directories
-        if metadata.file_type().is_file() {
-            let mut components = entry.path().components();
-
-            // Ensure we got `/` (absolute path) and then `manta`
-            assert_eq!(components.next(), Some(Component::RootDir));
-            assert_eq!(components.next().unwrap().as_os_str(), "manta");
-
-            let component = components.next().unwrap();
-
-            let account_uuid = if component.as_os_str() == "v2" {
-                // If the path starts with /manta/v2, the next component is the owner_uuid
-                components.next().unwrap().as_os_str().to_str().unwrap()
-            } else {
-                // If the path starts with /manta/ but then has a uuid instead
-                // of `v2`, that uuid is the creator uuid and this is a
-                // mantav1 or mantav2 dir-style path.
-                component.as_os_str().to_str().unwrap()
-            };
-
-            match accounts.get_mut(account_uuid) {
-                Some(account) => {
-                    let updated_bytes: u64 = account.bytes + metadata.len();
-                    let updated_objects: u64 = account.objects + 1;
-                    accounts.insert(
-                        account_uuid.to_string(),
-                        Account {
-                            bytes: updated_bytes,
-                            objects: updated_objects,
-                        },
-                    );
-                }
-                None => {
-                    let first_bytes: u64 = metadata.len();
-                    accounts.insert(
-                        account_uuid.to_string(),
-                        Account {
-                            bytes: first_bytes,
-                            objects: 1,
-                        },
-                    );
+/// Where the per-directory rollup cache is persisted between runs.
+const CACHE_FILE_PATH: &str = "/var/tmp/mako_rollup_state";
+
+/// A cached total for one directory: `(mtime, direct_bytes, direct_objects)`.
+/// `direct_bytes`/`direct_objects` cover only the files directly inside the
+/// directory, not its subdirectories. Since Manta objects are immutable once
+/// written, a directory's mtime only moves when entries are added or removed
+/// *directly inside it* -- a change several levels down a dir-style path
+/// does not touch an ancestor's mtime. So a cache hit only tells us the
+/// direct files are unchanged; we still have to recurse into every
+/// subdirectory to account for changes further down the tree.
+type CacheEntry = (u64, u64, u64);
+type DirCache = HashMap<PathBuf, CacheEntry>;
+
+/// Load the on-disk directory cache. Any problem reading or parsing it
+/// (missing file, corrupt line, etc.) is treated as "no cache", which falls
+/// back to a full scan rather than failing the rollup.
+fn load_cache(path: &Path) -> DirCache {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return DirCache::new(),
+    };
+
+    let mut cache = DirCache::new();
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return DirCache::new(),
+        };
+
+        let mut fields = line.rsplitn(4, '\t');
+        let objects = fields.next().and_then(|v| v.parse::<u64>().ok());
+        let bytes = fields.next().and_then(|v| v.parse::<u64>().ok());
+        let mtime = fields.next().and_then(|v| v.parse::<u64>().ok());
+        let dir = fields.next();
+
+        match (dir, mtime, bytes, objects) {
+            (Some(dir), Some(mtime), Some(bytes), Some(objects)) => {
+                cache.insert(PathBuf::from(dir), (mtime, bytes, objects));
+            }
+            _ => return DirCache::new(),
+        }
+    }
+
+    cache
+}
+
+/// Persist the directory cache for the next run. Best-effort: if we can't
+/// write it, the next run just falls back to a full scan.
+fn write_cache(path: &Path, cache: &DirCache) {
+    let mut file = match fs::File::create(path) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+
+    for (dir, (mtime, bytes, objects)) in cache.iter() {
+        let _ = writeln!(file, "{}\t{}\t{}\t{}", dir.display(), mtime, bytes, objects);
+    }
+}
+
+/// The directory's mtime, in whole seconds since the epoch, used as the
+/// cache invalidation token.
+fn dir_mtime_secs(metadata: &fs::Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// Figure out how many worker threads to scan with. Defaults to the number
+/// of CPUs on the box, but can be overridden with `--threads N` for testing
+/// or to leave headroom for other processes on a storage node.
+fn num_threads(args: &[String]) -> usize {
+    args.iter()
+        .position(|arg| arg == "--threads")
+        .and_then(|idx| args.get(idx + 1))
+        .and_then(|val| val.parse::<usize>().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+}
+
+/// Given one of the account/owner directories directly under `/manta`,
+/// derive the account uuid that every object beneath it belongs to.
+///
+/// `/manta/v2/<owner_uuid>` is a mantav2 owner directory, while
+/// `/manta/<uuid>` is a mantav1 (or dir-style mantav2) creator directory.
+/// Returns `None` rather than panicking when `dir` doesn't look like
+/// either shape, so one unexpected path under `/manta` doesn't abort the
+/// whole scan -- the caller reports it as a malformed path instead.
+fn account_uuid_for_top_level_dir(dir: &Path) -> Option<String> {
+    let mut components = dir.components();
+
+    if components.next() != Some(Component::RootDir) {
+        return None;
+    }
+    if components.next()?.as_os_str() != "manta" {
+        return None;
+    }
+
+    let component = components.next()?;
+
+    if component.as_os_str() == "v2" {
+        components.next().map(|c| c.as_os_str().to_string_lossy().into_owned())
+    } else {
+        Some(component.as_os_str().to_string_lossy().into_owned())
+    }
+}
+
+/// Enumerate the top-level account/owner directories under `/manta` so that
+/// each one can be dispatched to a worker as an independent unit of work.
+/// Any entry that doesn't fit the expected `/manta/<uuid>` or
+/// `/manta/v2/<owner_uuid>` shape -- a stray file directly under `/manta`,
+/// or a non-directory entry under `/manta/v2` -- is reported back as a
+/// malformed path instead of silently dropped, so `mako audit` actually
+/// sees it.
+fn top_level_account_dirs(root: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut dirs = Vec::new();
+    let mut malformed = Vec::new();
+
+    let entries = match root.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return (dirs, malformed),
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            malformed.push(path);
+            continue;
+        }
+
+        if path.file_name().map(|n| n == "v2").unwrap_or(false) {
+            if let Ok(owners) = path.read_dir() {
+                for owner in owners.flatten() {
+                    let owner_path = owner.path();
+                    if owner_path.is_dir() {
+                        dirs.push(owner_path);
+                    } else {
+                        malformed.push(owner_path);
+                    }
                 }
             }
+        } else {
+            dirs.push(path);
         }
     }
 
-    println!(
-        "# HELP used_bytes The current number of bytes used on a mako\n# TYPE used_bytes gauge"
+    (dirs, malformed)
+}
+
+/// Recursively total the bytes and objects under `dir`. When a directory's
+/// mtime matches the cache, its direct files' contribution is taken from
+/// the cache without re-stat'ing them -- but every subdirectory is still
+/// walked regardless of the parent's cache status, since a subdirectory can
+/// change without its parent's mtime moving at all. This keeps the cache
+/// sound for dir-style (nested) account layouts, at the cost of still
+/// having to `read_dir` every directory on the path; the win is skipping
+/// the `metadata()` call on each individual file in directories that
+/// haven't changed.
+fn scan_dir_cached(
+    dir: &Path,
+    old_cache: &DirCache,
+    new_cache: &mut DirCache,
+    scanned_files: &AtomicU64,
+    cache_hit_dirs: &AtomicU64,
+    cache_miss_dirs: &AtomicU64,
+) -> (u64, u64) {
+    let metadata = match fs::metadata(dir) {
+        Ok(metadata) => metadata,
+        Err(_) => return (0, 0),
+    };
+    let mtime = dir_mtime_secs(&metadata);
+
+    let cached_direct = mtime.and_then(|mtime| {
+        old_cache
+            .get(dir)
+            .filter(|&&(cached_mtime, _, _)| cached_mtime == mtime)
+    });
+
+    match cached_direct {
+        Some(_) => cache_hit_dirs.fetch_add(1, Ordering::Relaxed),
+        None => cache_miss_dirs.fetch_add(1, Ordering::Relaxed),
+    };
+
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return (0, 0),
+    };
+
+    let mut direct_bytes = 0u64;
+    let mut direct_objects = 0u64;
+    let mut subtree_bytes = 0u64;
+    let mut subtree_objects = 0u64;
+
+    for entry in entries.flatten() {
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+
+        if file_type.is_dir() {
+            // Always descend: a subdirectory's own mtime is what tells us
+            // whether *it* changed, and `dir`'s mtime can't tell us that.
+            let (sub_bytes, sub_objects) = scan_dir_cached(
+                &entry.path(),
+                old_cache,
+                new_cache,
+                scanned_files,
+                cache_hit_dirs,
+                cache_miss_dirs,
+            );
+            subtree_bytes += sub_bytes;
+            subtree_objects += sub_objects;
+        } else if file_type.is_file() && cached_direct.is_none() {
+            // Only re-stat direct files when we don't already have a valid
+            // cached total for them.
+            if let Ok(entry_metadata) = entry.metadata() {
+                scanned_files.fetch_add(1, Ordering::Relaxed);
+                direct_bytes += entry_metadata.len();
+                direct_objects += 1;
+            }
+        }
+    }
+
+    let (direct_bytes, direct_objects) = match cached_direct {
+        Some(&(_, bytes, objects)) => (bytes, objects),
+        None => (direct_bytes, direct_objects),
+    };
+
+    if let Some(mtime) = mtime {
+        new_cache.insert(dir.to_path_buf(), (mtime, direct_bytes, direct_objects));
+    }
+
+    (direct_bytes + subtree_bytes, direct_objects + subtree_objects)
+}
+
+/// Walk a single account's subtree and return its local byte/object totals
+/// plus the directory cache entries collected along the way. Every file
+/// under `dir` belongs to `account_uuid`, since `dir` is itself one of the
+/// top-level account/owner directories.
+fn scan_account_dir(
+    account_uuid: &str,
+    dir: &Path,
+    old_cache: &DirCache,
+    scanned_files: &AtomicU64,
+    cache_hit_dirs: &AtomicU64,
+    cache_miss_dirs: &AtomicU64,
+) -> (HashMap<String, Account>, DirCache) {
+    let mut new_cache = DirCache::new();
+    let (bytes, objects) = scan_dir_cached(
+        dir,
+        old_cache,
+        &mut new_cache,
+        scanned_files,
+        cache_hit_dirs,
+        cache_miss_dirs,
     );
 
+    let mut local: HashMap<String, Account> = HashMap::new();
+    local.insert(account_uuid.to_string(), Account { bytes, objects });
+
+    (local, new_cache)
+}
+
+/// Fold a worker's local totals into the final, global set of accounts.
+fn merge_accounts(mut acc: HashMap<String, Account>, other: HashMap<String, Account>) -> HashMap<String, Account> {
+    for (k, v) in other {
+        acc.entry(k)
+            .and_modify(|existing| {
+                existing.bytes += v.bytes;
+                existing.objects += v.objects;
+            })
+            .or_insert(v);
+    }
+    acc
+}
+
+/// Figure out the address to serve `/metrics` on, if the caller asked for
+/// daemon mode via `--serve <addr>` instead of the default one-shot print.
+fn serve_addr(args: &[String]) -> Option<&str> {
+    args.iter()
+        .position(|arg| arg == "--serve")
+        .and_then(|idx| args.get(idx + 1))
+        .map(|s| s.as_str())
+}
+
+/// How often (in seconds) the daemon should recompute its metrics snapshot
+/// in the background via `--interval N`, independent of scrape cadence. If
+/// unset, daemon mode falls back to recomputing on every scrape instead.
+fn serve_interval_secs(args: &[String]) -> Option<u64> {
+    args.iter()
+        .position(|arg| arg == "--interval")
+        .and_then(|idx| args.get(idx + 1))
+        .and_then(|val| val.parse::<u64>().ok())
+        .filter(|&secs| secs > 0)
+}
+
+/// `mako audit` runs the same scan but reports on malformed paths instead
+/// of emitting Prometheus metrics.
+fn is_audit_mode(args: &[String]) -> bool {
+    args.get(1).map(|arg| arg == "audit").unwrap_or(false)
+}
+
+/// Where to write the list of malformed paths, if the caller asked for one
+/// via `--malformed-log <path>`. Writing the log is optional; the metrics
+/// output carries the `mako_malformed_paths_total` gauge either way, and
+/// `mako audit` reports the count in its own report instead.
+fn malformed_log_path(args: &[String]) -> Option<&str> {
+    args.iter()
+        .position(|arg| arg == "--malformed-log")
+        .and_then(|idx| args.get(idx + 1))
+        .map(|s| s.as_str())
+}
+
+fn write_malformed_log(path: &str, malformed_paths: &[PathBuf]) {
+    let mut file = match fs::File::create(path) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+
+    for p in malformed_paths {
+        let _ = writeln!(file, "{}", p.display());
+    }
+}
+
+/// The outcome of one full rollup pass: the per-account totals plus
+/// everything needed to report on how the scan went.
+struct RollupResult {
+    accounts: HashMap<String, Account>,
+    duration: Duration,
+    scanned_files: u64,
+    cache_hit_dirs: u64,
+    cache_miss_dirs: u64,
+    malformed_paths: Vec<PathBuf>,
+}
+
+/// Run one full rollup pass over `/manta`. Directories that don't match the
+/// expected account/owner layout are collected as malformed paths rather
+/// than aborting the scan, so a single unexpected path still leaves every
+/// well-formed account's metrics intact.
+fn run_rollup() -> RollupResult {
+    let start = Instant::now();
+    let scanned_files = AtomicU64::new(0);
+    let cache_hit_dirs = AtomicU64::new(0);
+    let cache_miss_dirs = AtomicU64::new(0);
+
+    let old_cache = load_cache(Path::new(CACHE_FILE_PATH));
+
+    let mut work_units: Vec<(String, PathBuf)> = Vec::new();
+    let (candidate_dirs, mut malformed_paths) = top_level_account_dirs(Path::new("/manta"));
+
+    for dir in candidate_dirs {
+        match account_uuid_for_top_level_dir(&dir) {
+            Some(account_uuid) => work_units.push((account_uuid, dir)),
+            None => malformed_paths.push(dir),
+        }
+    }
+
+    let (accounts, new_cache) = work_units
+        .par_iter()
+        .map(|(account_uuid, dir)| {
+            scan_account_dir(
+                account_uuid,
+                dir,
+                &old_cache,
+                &scanned_files,
+                &cache_hit_dirs,
+                &cache_miss_dirs,
+            )
+        })
+        .reduce(
+            || (HashMap::new(), DirCache::new()),
+            |(accounts_a, mut cache_a), (accounts_b, cache_b)| {
+                cache_a.extend(cache_b);
+                (merge_accounts(accounts_a, accounts_b), cache_a)
+            },
+        );
+
+    write_cache(Path::new(CACHE_FILE_PATH), &new_cache);
+
+    RollupResult {
+        accounts,
+        duration: start.elapsed(),
+        scanned_files: scanned_files.load(Ordering::Relaxed),
+        cache_hit_dirs: cache_hit_dirs.load(Ordering::Relaxed),
+        cache_miss_dirs: cache_miss_dirs.load(Ordering::Relaxed),
+        malformed_paths,
+    }
+}
+
+/// Render a rollup pass as Prometheus text exposition format, the same
+/// output whether it's printed once to stdout or served to a scraper.
+fn render_metrics(result: &RollupResult) -> String {
+    let RollupResult {
+        accounts,
+        duration,
+        scanned_files,
+        cache_hit_dirs,
+        cache_miss_dirs,
+        malformed_paths,
+    } = result;
+
+    let mut out = String::new();
+
+    writeln!(out, "# HELP used_bytes The current number of bytes used on a mako\n# TYPE used_bytes gauge").unwrap();
+
     for (k, v) in accounts.iter() {
-        println!("used_bytes{{account=\"{}\"}} {}", k, v.bytes);
+        writeln!(out, "used_bytes{{account=\"{}\"}} {}", k, v.bytes).unwrap();
     }
 
-    println!("# HELP The current number of objects on a mako\n# TYPE object_count gauge");
+    writeln!(out, "# HELP The current number of objects on a mako\n# TYPE object_count gauge").unwrap();
 
     for (k, v) in accounts.iter() {
-        println!("object_count{{account=\"{}\"}} {}", k, v.objects);
+        writeln!(out, "object_count{{account=\"{}\"}} {}", k, v.objects).unwrap();
     }
 
-    println!("# HELP rollup_duration_seconds Duration in seconds of the mako rollup process");
-    println!(
+    writeln!(out, "# HELP rollup_duration_seconds Duration in seconds of the mako rollup process").unwrap();
+    writeln!(
+        out,
         "# TYPE rollup_duration_seconds gauge\nrollup_duration_seconds {}",
-        start.elapsed().as_secs()
-    );
+        duration.as_secs()
+    )
+    .unwrap();
+
+    writeln!(out, "# HELP rollup_scanned_files_total Number of files examined by the mako rollup process").unwrap();
+    writeln!(
+        out,
+        "# TYPE rollup_scanned_files_total counter\nrollup_scanned_files_total {}",
+        scanned_files
+    )
+    .unwrap();
+
+    writeln!(out, "# HELP rollup_cache_hit_dirs Number of directories whose cached direct file totals were reused").unwrap();
+    writeln!(
+        out,
+        "# TYPE rollup_cache_hit_dirs gauge\nrollup_cache_hit_dirs {}",
+        cache_hit_dirs
+    )
+    .unwrap();
+
+    writeln!(out, "# HELP rollup_cache_miss_dirs Number of directories that were re-scanned because the cache was stale or missing").unwrap();
+    writeln!(
+        out,
+        "# TYPE rollup_cache_miss_dirs gauge\nrollup_cache_miss_dirs {}",
+        cache_miss_dirs
+    )
+    .unwrap();
+
+    writeln!(out, "# HELP mako_malformed_paths_total Number of unexpected paths under /manta that could not be parsed as an account/owner directory").unwrap();
+    writeln!(
+        out,
+        "# TYPE mako_malformed_paths_total gauge\nmako_malformed_paths_total {}",
+        malformed_paths.len()
+    )
+    .unwrap();
 
     let unix_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
-    println!("# HELP rollup_last_run_time Last run of the mako rollup process expressed as a UNIX timestamp");
-    println!(
+    writeln!(out, "# HELP rollup_last_run_time Last run of the mako rollup process expressed as a UNIX timestamp").unwrap();
+    writeln!(
+        out,
         "# TYPE rollup_last_run_time gauge\nrollup_last_run_time {}",
         unix_time.unwrap().as_secs()
-    );
+    )
+    .unwrap();
+
+    out
+}
+
+/// Render the human-readable report produced by `mako audit`: a summary
+/// plus the full list of malformed paths for postmortem, instead of
+/// Prometheus metrics.
+fn render_audit_report(result: &RollupResult) -> String {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "mako audit: {} accounts scanned, {} malformed paths found",
+        result.accounts.len(),
+        result.malformed_paths.len()
+    )
+    .unwrap();
+
+    for path in &result.malformed_paths {
+        writeln!(out, "malformed path: {}", path.display()).unwrap();
+    }
+
+    out
+}
+
+/// Where a scrape gets its `RollupResult` from: either a background thread
+/// keeps `Snapshot` warm on a fixed cadence and every scrape just reads it,
+/// or (with no `--interval`) each scrape runs its own full rollup pass.
+/// Either way a scrape never blocks behind another in-flight scrape, since
+/// `serve` handles each connection on its own thread.
+enum MetricsSource {
+    Snapshot(Arc<Mutex<Option<RollupResult>>>),
+    OnDemand,
+}
+
+/// Recompute `/manta` on a fixed cadence and publish the result for scrapes
+/// to read, so the expensive walk runs on its own schedule instead of once
+/// per scrape.
+fn run_recompute_loop(interval: Duration, malformed_log: Option<String>, snapshot: Arc<Mutex<Option<RollupResult>>>) {
+    loop {
+        let result = run_rollup();
+        if let Some(path) = &malformed_log {
+            write_malformed_log(path, &result.malformed_paths);
+        }
+        *snapshot.lock().unwrap() = Some(result);
+        thread::sleep(interval);
+    }
+}
+
+/// Read and discard the HTTP request line/headers from a scrape connection,
+/// then write back the current metrics as a `200 OK` response, or a
+/// `503 Service Unavailable` if the background recompute loop has not
+/// produced its first snapshot yet. This is a
+/// deliberately minimal HTTP/1.1 responder: just enough for Prometheus (or
+/// curl) to scrape `/metrics` without pulling in an HTTP server dependency.
+fn handle_scrape(stream: &mut TcpStream, source: &MetricsSource, malformed_log: Option<&str>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(clone) => clone,
+        Err(_) => return,
+    });
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+
+    // Drain the rest of the headers before responding. Each connection gets
+    // a single response and is then closed (`Connection: close`).
+    loop {
+        let mut header_line = String::new();
+        match reader.read_line(&mut header_line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) if header_line == "\r\n" || header_line == "\n" => break,
+            Ok(_) => continue,
+        }
+    }
+
+    let response = match source {
+        MetricsSource::Snapshot(snapshot) => match snapshot.lock().unwrap().as_ref() {
+            Some(result) => {
+                let body = render_metrics(result);
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            }
+            // The background recompute loop hasn't completed its first pass yet.
+            None => {
+                let body = "mako rollup: first scan still in progress\n";
+                format!(
+                    "HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            }
+        },
+        MetricsSource::OnDemand => {
+            let result = run_rollup();
+            if let Some(path) = malformed_log {
+                write_malformed_log(path, &result.malformed_paths);
+            }
+            let body = render_metrics(&result);
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+    };
+
+    let _ = stream.write_all(response.as_bytes());
+}
+
+/// Run as a long-lived daemon, serving metrics on `/metrics` instead of the
+/// default one-shot stdout print. With `--interval N`, a background thread
+/// recomputes the snapshot every `N` seconds and scrapes just read it,
+/// decoupling scrape cadence from recompute cost; without it, each scrape
+/// triggers its own rollup pass. Either way, each connection is handled on
+/// its own thread so one slow scrape can't stall the rest.
+fn serve(addr: &str, malformed_log: Option<String>, interval: Option<u64>) {
+    let listener = TcpListener::bind(addr).expect("failed to bind --serve address");
+    println!("Serving mako rollup metrics on http://{}/metrics", addr);
+
+    let source = match interval {
+        Some(secs) => {
+            let snapshot = Arc::new(Mutex::new(None));
+            let recompute_log = malformed_log.clone();
+            let recompute_snapshot = Arc::clone(&snapshot);
+            thread::spawn(move || run_recompute_loop(Duration::from_secs(secs), recompute_log, recompute_snapshot));
+            MetricsSource::Snapshot(snapshot)
+        }
+        None => MetricsSource::OnDemand,
+    };
+    let source = Arc::new(source);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let source = Arc::clone(&source);
+        let malformed_log = malformed_log.clone();
+        thread::spawn(move || handle_scrape(&mut stream, &source, malformed_log.as_deref()));
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let threads = num_threads(&args);
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .expect("failed to build rayon thread pool");
+
+    let malformed_log = malformed_log_path(&args);
+
+    if is_audit_mode(&args) {
+        let result = run_rollup();
+        if let Some(path) = malformed_log {
+            write_malformed_log(path, &result.malformed_paths);
+        }
+        print!("{}", render_audit_report(&result));
+        return;
+    }
+
+    match serve_addr(&args) {
+        Some(addr) => serve(addr, malformed_log.map(String::from), serve_interval_secs(&args)),
+        None => {
+            let result = run_rollup();
+            if let Some(path) = malformed_log {
+                write_malformed_log(path, &result.malformed_paths);
+            }
+            print!("{}", render_metrics(&result));
+        }
+    }
 }